@@ -1,4 +1,6 @@
+use std::any::Any;
 use std::cell::Cell;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use mpi::collective::SystemOperation;
@@ -18,7 +20,16 @@ pub struct Balancer<O> {
     pub workers: usize,
     pub rank: usize,
     pub size: usize,
+    /// Worker count reported by every rank, indexed by rank. Used to weight
+    /// the partitioning helpers so larger nodes receive proportionally more
+    /// work. Exposed so callers can inspect the resulting layout.
+    pub weights: Vec<usize>,
     work: Cell<Option<LocalWork<O>>>,
+    /// Closures registered for service mode, keyed by task id. Each value is a
+    /// boxed `Fn(&I) -> O` for some concrete `I`, erased so tasks with
+    /// different inputs can share one registry; `serve`/`dispatch` recover the
+    /// type at their call site.
+    tasks: HashMap<u32, Box<dyn Any + Send + Sync>>,
 }
 
 impl<O> Balancer<O>
@@ -44,18 +55,116 @@ where
             println!(" Workers (rank 0) : {workers} ");
             println!("--------------------------------------");
         }
+        // Collect every rank's worker count so partitioning can be weighted.
+        let mut weights = vec![0usize; size];
+        world.all_gather_into(&workers, &mut weights[..]);
+
         Balancer {
             universe,
             world,
             workers,
             rank,
             size,
+            weights,
             work: Cell::new(None),
+            tasks: HashMap::new(),
+        }
+    }
+
+    /// Task id that tells a parked `serve` loop to exit.
+    pub const SHUTDOWN: u32 = u32::MAX;
+
+    /// Registers a closure under an integer task id for service mode. All
+    /// ranks that will participate must register the same id so that a
+    /// broadcast `dispatch` finds a matching closure everywhere.
+    pub fn register_task<I, F>(&mut self, id: u32, work: F)
+    where
+        I: Send + Sync + 'static,
+        F: Fn(&I) -> O + Send + Sync + 'static,
+        O: 'static,
+    {
+        let boxed: Box<dyn Fn(&I) -> O + Send + Sync> = Box::new(work);
+        self.tasks.insert(id, Box::new(boxed));
+    }
+
+    /// Parks a non-root rank in a loop that receives a broadcast task id, takes
+    /// its share of the distributed payload, runs the matching registered
+    /// closure, and sends the results back to rank 0 — looping until rank 0
+    /// broadcasts [`Balancer::SHUTDOWN`]. This reuses one worker pool across
+    /// successive rounds instead of rebuilding control flow each iteration.
+    pub fn serve<I>(&self)
+    where
+        I: Send + Sync + Equivalence + 'static,
+        O: Send + Sync + 'static,
+    {
+        loop {
+            let mut id: u32 = 0;
+            self.world.process_at_rank(0).broadcast_into(&mut id);
+            if id == Self::SHUTDOWN {
+                break;
+            }
+            let data: Vec<I> = self.distribute(None).unwrap();
+            let task = self
+                .tasks
+                .get(&id)
+                .and_then(|b| b.downcast_ref::<Box<dyn Fn(&I) -> O + Send + Sync>>())
+                .expect("serve: received an unregistered or mistyped task id");
+            self.work(&data, |i| task(i));
+            self.collect();
         }
     }
 
-    /// Calculates local set of items on which to work on.
+    /// Rank 0 entry point matching [`Balancer::serve`]: broadcasts the task id,
+    /// distributes `data` across the parked ranks, runs the local share, and
+    /// collects the combined output. Returns `Some(Vec<O>)` on rank 0.
+    pub fn dispatch<I>(&self, id: u32, data: Option<Vec<I>>) -> Option<Vec<O>>
+    where
+        I: Send + Sync + Equivalence + 'static,
+        O: Send + Sync + 'static,
+    {
+        let mut id = id;
+        self.world.process_at_rank(0).broadcast_into(&mut id);
+        let ours = self.distribute(data).unwrap();
+        let task = self
+            .tasks
+            .get(&id)
+            .and_then(|b| b.downcast_ref::<Box<dyn Fn(&I) -> O + Send + Sync>>())
+            .expect("dispatch: unregistered or mistyped task id");
+        self.work(&ours, |i| task(i));
+        self.collect()
+    }
+
+    /// Broadcasts the shutdown sentinel so every parked [`Balancer::serve`]
+    /// loop exits. Call once from rank 0 when no more rounds remain.
+    pub fn stop_serving(&self) {
+        let mut id = Self::SHUTDOWN;
+        self.world.process_at_rank(0).broadcast_into(&mut id);
+    }
+
+    /// Cumulative, worker-weighted `[l, r)` range of an input of length `len`
+    /// belonging to this rank. Cumulative offsets keep the ranges disjoint and
+    /// covering the whole input.
+    fn weighted_range(&self, len: usize) -> (usize, usize) {
+        let total: usize = self.weights.iter().sum();
+        let before: usize = self.weights[..self.rank].iter().sum();
+        let l = len * before / total;
+        let r = len * (before + self.weights[self.rank]) / total;
+        (l, r)
+    }
+
+    /// Calculates local set of items on which to work on, weighting each rank's
+    /// share by its worker count.
     pub fn get_subset<'b, I>(&self, items: &'b [I]) -> &'b [I]
+    where
+        I: Send + Sync,
+    {
+        let (l, r) = self.weighted_range(items.len());
+        &items[l..r]
+    }
+
+    /// Calculates local set of items using the old uniform split, ignoring the
+    /// gathered worker weights.
+    pub fn get_subset_uniform<'b, I>(&self, items: &'b [I]) -> &'b [I]
     where
         I: Send + Sync,
     {
@@ -76,11 +185,7 @@ where
         O: Send,
     {
         // Gather and return local set of items
-        let chunk_size = div_ceil(items.len(), self.size);
-        let (l, r) = (
-            self.rank * chunk_size,
-            ((self.rank + 1) * chunk_size).min(items.len()),
-        );
+        let (l, r) = self.weighted_range(items.len());
         let our_items: &'b [I] = &items[l..r];
 
         // Carry out work on local node threads
@@ -90,6 +195,59 @@ where
         self.work.set(Some(LocalWork { output }));
     }
 
+    /// Maps each local item to an `O`, folds the local results into a single
+    /// partial, and then combines one partial per rank down onto rank 0.
+    ///
+    /// The local fold is carried out with rayon's `reduce`, and rank 0 then
+    /// folds in the other ranks' partials one at a time, in rank order.
+    /// `merger` **must be associative and commutative** and `neutral` **must be the
+    /// identity** for it (i.e. `merger(x, &neutral) == x`). Only the combined
+    /// `O` flows back to rank 0, so this is much cheaper than `collect` when
+    /// the caller only wants an aggregate.
+    ///
+    /// Returns `Some(O)` on rank 0 and `None` on every other rank.
+    pub fn work_reduce<'b, I, F>(
+        &self,
+        items: &'b [I],
+        work: F,
+        merger: fn(O, &O) -> O,
+        neutral: O,
+    ) -> Option<O>
+    where
+        I: Send + Sync,
+        F: Fn(&'b I) -> O + Send + Sync,
+        O: Send + Sync + Clone,
+    {
+        // Gather local set of items, weighted by worker count like the other
+        // partitioning helpers.
+        let (l, r) = self.weighted_range(items.len());
+        let our_items: &'b [I] = &items[l..r];
+
+        // Fold the local results in parallel, starting from the identity
+        let local: O = our_items
+            .into_par_iter()
+            .map(|i| work(i))
+            .reduce(|| neutral.clone(), |a, b| merger(a, &b));
+
+        if self.rank == 0 {
+            // Fold in the single partial `O` held by each other rank
+            let mut acc = merger(neutral.clone(), &local);
+            for rank in 1..self.size {
+                let (their_partial, _status) =
+                    self.world.process_at_rank(rank as i32).receive_vec::<O>();
+                acc = merger(acc, &their_partial[0]);
+            }
+            self.world.barrier();
+            Some(acc)
+        } else {
+            self.world
+                .process_at_rank(0)
+                .send(std::slice::from_ref(&local));
+            self.world.barrier();
+            None
+        }
+    }
+
     /// Works on the entire set provided
     pub fn work<'b, I, F>(&self, items: &'b [I], work: F)
     where
@@ -104,6 +262,111 @@ where
         self.work.set(Some(LocalWork { output }));
     }
 
+    /// Master/worker scheduling for inputs whose per-item cost is uneven.
+    ///
+    /// Unlike `work_subset`, which statically cuts `items` into one contiguous
+    /// chunk per rank sized by its worker count, this balances load by
+    /// *demand*: rank 0 acts as a
+    /// coordinator holding a queue of index ranges, and every other rank
+    /// repeatedly asks for work, processes the handed-out range locally with
+    /// rayon, sends the results back, and loops until the queue is drained and
+    /// the coordinator replies with a termination sentinel (an empty range).
+    /// Rank 0 interleaves serving those requests with working its own chunk
+    /// whenever no request is pending, so fast ranks keep pulling more while
+    /// a slow rank holds only one chunk at a time. Rank 0 returns once every
+    /// handed-out chunk has come back.
+    ///
+    /// Results are reassembled in input order and returned as `Some(Vec<O>)`
+    /// on rank 0 and `None` on every other rank.
+    pub fn work_dynamic<'b, I, F>(&self, items: &'b [I], work: F) -> Option<Vec<O>>
+    where
+        I: Send + Sync,
+        F: Fn(&'b I) -> O + Send + Sync,
+        O: Send + Sync,
+    {
+        // Tags distinguishing the three messages in the request/response loop.
+        const REQUEST: i32 = 1;
+        const ASSIGN: i32 = 2;
+        const RESULT: i32 = 3;
+
+        // Chunk granularity: several chunks per rank so that idle workers can
+        // keep stealing, while still amortizing each round-trip.
+        let chunk = div_ceil(items.len(), (self.size * 8).max(1)).max(1);
+
+        if self.rank != 0 {
+            // Worker: pull ranges until handed the empty-range sentinel.
+            let empty: Vec<O> = Vec::new();
+            loop {
+                self.world
+                    .process_at_rank(0)
+                    .send_with_tag(&empty[..], REQUEST);
+                let (range, _status) = self
+                    .world
+                    .process_at_rank(0)
+                    .receive_vec_with_tag::<usize>(ASSIGN);
+                let (l, r) = (range[0], range[1]);
+                if l == r {
+                    break;
+                }
+                let res: Vec<O> = items[l..r].into_par_iter().map(|i| work(i)).collect();
+                self.world.process_at_rank(0).send_with_tag(&res[..], RESULT);
+            }
+            self.world.barrier();
+            return None;
+        }
+
+        // Coordinator (rank 0).
+        let mut out: Vec<(usize, Vec<O>)> = Vec::new();
+        let mut next = 0usize;
+        // Range last handed to each rank, so returned results land in order.
+        let mut assigned: HashMap<i32, (usize, usize)> = HashMap::new();
+        let mut active = self.size - 1; // non-root ranks still pulling work
+
+        while active > 0 || next < items.len() {
+            // While we still have our own work, only peek (non-blocking) so an
+            // idle moment is spent computing rather than serving. Once our work
+            // is drained, block on the probe so the coordinator sleeps until
+            // the next request instead of spinning at 100% CPU.
+            let probed = if next < items.len() {
+                self.world.any_process().immediate_matched_probe()
+            } else {
+                Some(self.world.any_process().matched_probe())
+            };
+
+            if let Some((msg, status)) = probed {
+                let src = status.source_rank();
+                let tag = status.tag();
+                let (payload, _status) = msg.matched_receive_vec::<O>();
+                if tag == REQUEST {
+                    // Hand out the next range, or the termination sentinel.
+                    let (l, r) = (next, (next + chunk).min(items.len()));
+                    next = r;
+                    assigned.insert(src, (l, r));
+                    self.world.process_at_rank(src).send_with_tag(&[l, r], ASSIGN);
+                    if l == r {
+                        active -= 1;
+                    }
+                } else {
+                    // RESULT: place the worker's output at the range we gave it.
+                    let (l, _r) = assigned[&src];
+                    out.push((l, payload));
+                }
+            } else if next < items.len() {
+                // Nothing pending: work a chunk ourselves.
+                let (l, r) = (next, (next + chunk).min(items.len()));
+                next = r;
+                let res: Vec<O> = items[l..r].into_par_iter().map(|i| work(i)).collect();
+                out.push((l, res));
+            }
+        }
+
+        // Reassemble in input order.
+        out.sort_by_key(|(l, _)| *l);
+        let result: Vec<O> = out.into_iter().flat_map(|(_, v)| v).collect();
+        self.world.barrier();
+        Some(result)
+    }
+
     /// Distributes items for work
     pub fn distribute<'b, I>(&self, items: Option<Vec<I>>) -> Option<Vec<I>>
     where
@@ -112,14 +375,36 @@ where
         // Gather and return local set of items
         if self.rank == 0 && self.size > 1 {
             let mut items = items.unwrap();
-            let chunk_size = div_ceil(items.len(), self.size);
-            let mut rank = 1;
-            let ours: Vec<I> = items.drain(..chunk_size).collect();
-            while !items.is_empty() {
-                let theirs: Vec<I> = items.drain(..chunk_size.min(items.len())).collect();
-                self.world.process_at_rank(rank).send(&theirs);
-                rank += 1
+            let len = items.len();
+            let total: usize = self.weights.iter().sum();
+            // Weighted, cumulative offsets; drain from the front so each rank
+            // gets a disjoint contiguous slice sized by its worker count.
+            let mut cum = self.weights[0];
+            let ours: Vec<I> = items.drain(..(len * cum / total)).collect();
+            let mut sendbufs: Vec<Vec<I>> = Vec::with_capacity(self.size - 1);
+            for rank in 1..self.size {
+                let start = len * cum / total;
+                cum += self.weights[rank];
+                let end = len * cum / total;
+                sendbufs.push(items.drain(..(end - start)).collect());
             }
+            // Fire every scatter off with a non-blocking send so the next
+            // chunk can leave while the previous one is still in transit,
+            // then wait on the whole batch at once.
+            mpi::request::scope(|scope| {
+                let reqs: Vec<_> = sendbufs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, buf)| {
+                        self.world
+                            .process_at_rank((i + 1) as i32)
+                            .immediate_send(scope, &buf[..])
+                    })
+                    .collect();
+                for req in reqs {
+                    req.wait();
+                }
+            });
             self.world.barrier();
             Some(ours)
         } else {
@@ -132,20 +417,29 @@ where
     pub fn collect(&self) -> Option<Vec<O>> {
         // Get rank output
         let work = self.work.replace(None)?;
-        let mut output: Vec<O> = work.output;
+        let output: Vec<O> = work.output;
 
         if self.rank == 0 {
-            // Collect outputs from all other ranks
-            for rank in 1..self.size {
-                let (mut rank_output, _status) =
-                    self.world.process_at_rank(rank as i32).receive_vec::<O>();
-                output.append(&mut rank_output);
+            // Receive from whichever rank finishes first (via matched probe)
+            // instead of iterating ranks in fixed order, but slot each result
+            // back into rank order so the concatenation still matches the
+            // input layout.
+            let mut parts: Vec<Vec<O>> = (0..self.size).map(|_| Vec::new()).collect();
+            parts[0] = output;
+            for _ in 1..self.size {
+                let (msg, status) = self.world.any_process().matched_probe();
+                let src = status.source_rank() as usize;
+                let (rank_output, _status) = msg.matched_receive_vec::<O>();
+                parts[src] = rank_output;
             }
 
             // If rank 0 return output
             self.world.barrier();
-            Some(output)
+            Some(parts.into_iter().flatten().collect())
         } else {
+            // Nothing local to overlap with before the barrier, so a plain
+            // blocking send is all this rank needs; the pipelining win is on
+            // rank 0's out-of-order fan-in above.
             self.world.process_at_rank(0).send(&output);
             // If not rank 0 return None
             self.world.barrier();